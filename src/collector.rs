@@ -0,0 +1,611 @@
+//! Collectors gather the individual sections of a [`ReportData`].
+
+use {
+    std::{
+        collections::HashMap,
+        pin::Pin,
+        time::Duration,
+    },
+    itertools::Itertools as _,
+    systemstat::{
+        Platform as _,
+        System,
+    },
+    tokio::process::Command,
+    wheel::traits::IoResultExt as _,
+    crate::{
+        Config,
+        CargoUpdateCheckError,
+        CleanupAction,
+        CleanupResult,
+        Error,
+        FileSystemUsage,
+        ReportData,
+        check_cargo_updates,
+        check_with_timeout,
+    },
+};
+#[cfg(unix)] use {
+    std::{
+        iter,
+        num::ParseIntError,
+        path::Path,
+        str::FromStr as _,
+    },
+    futures::stream::TryStreamExt as _,
+    lazy_regex::{
+        regex_captures,
+        regex_is_match,
+    },
+    serde::Deserialize,
+    tokio::io::{
+        AsyncBufReadExt as _,
+        BufReader,
+    },
+    tokio_stream::wrappers::LinesStream,
+    wheel::fs::{
+        self,
+        File,
+    },
+    crate::{
+        CronAptLog,
+        NeedRestartDetails,
+        NixosUpgrade,
+        RebootComponent,
+        RebootReason,
+        output_with_timeout,
+    },
+};
+#[cfg(windows)] use {
+    wheel::traits::CommandExt as _,
+    crate::ScoopUpdate,
+};
+
+/// The result of a collector, which writes its section into the report.
+pub type Section = Box<dyn FnOnce(&mut ReportData) + Send>;
+
+/// Information available to all collectors.
+pub struct Context<'a> {
+    pub config: &'a Config,
+    pub os_info: &'a os_info::Info,
+    pub verbose: bool,
+    /// The report is only being printed, so collectors should not modify the system.
+    pub dry_run: bool,
+}
+
+/// A check which fills in a section of the report.
+pub trait Collector: Send + Sync {
+    /// A short identifier for this collector, used in progress messages and as the key in [`ReportData::section_errors`].
+    fn name(&self) -> &'static str;
+
+    /// Whether this collector should run on the given operating system. Defaults to `true`.
+    fn applies_to(&self, os_type: os_info::Type) -> bool {
+        let _ = os_type;
+        true
+    }
+
+    /// Whether this collector should only start once all other collectors have finished, e.g. because it measures state which other collectors change. Defaults to `false`.
+    /// Collectors for which this is `true` still run concurrently with each other.
+    fn run_last(&self) -> bool { false }
+
+    /// Runs the check. The returned [`Section`] is applied to the report once all collectors have run.
+    fn collect<'a>(&'a self, ctx: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>>;
+}
+
+/// The collectors used by [`ReportData::new`].
+pub fn default_collectors() -> Vec<Box<dyn Collector>> {
+    vec![
+        Box::new(CargoUpdates),
+        Box::new(CronApt),
+        Box::new(DiskSpace),
+        Box::new(NeedRestart),
+        #[cfg(unix)] Box::new(NixosUpgradeStatus),
+        #[cfg(unix)] Box::new(OldConfFilesCheck),
+        #[cfg(unix)] Box::new(OsVersion),
+        #[cfg(windows)] Box::new(ScoopUpdates),
+    ]
+}
+
+/// Checks for updates to packages installed via `cargo install` and attempts to install them. In dry-run mode, only reports pending updates.
+pub struct CargoUpdates;
+
+impl Collector for CargoUpdates {
+    fn name(&self) -> &'static str { "cargo" }
+
+    fn collect<'a>(&'a self, ctx: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let timeout = Duration::from_secs(ctx.config.timeouts.cargo_install_update);
+            let (cargo_updates, cargo_updates_git, cargo_update_check_error_debug, cargo_update_check_error_display) = match check_cargo_updates(&ctx.config.cargo, ctx.config.root, true, timeout).await {
+                Ok((cargo_updates, cargo_updates_git)) => if !ctx.dry_run && (!cargo_updates.is_empty() || !cargo_updates_git.is_empty()) {
+                    let mut command = ctx.config.cargo.install_update_command(ctx.config.root);
+                    command.arg("--all");
+                    command.arg("--git");
+                    if ctx.verbose { println!("attempting to install Cargo updates") }
+                    let result = async {
+                        check_with_timeout("cargo install-update", timeout, &mut command).await??;
+                        Ok::<_, CargoUpdateCheckError>(())
+                    }.await;
+                    if let Err(e) = result {
+                        if ctx.verbose { println!("installing Cargo updates failed, reporting") }
+                        (Some(cargo_updates), Some(cargo_updates_git), Some(format!("{e:?}")), Some(e.to_string()))
+                    } else {
+                        if ctx.verbose { println!("Cargo updates successfully installed") }
+                        (Some(HashMap::default()), Some(HashMap::default()), None, None)
+                    }
+                } else {
+                    (Some(cargo_updates), Some(cargo_updates_git), None, None)
+                },
+                Err(e) => (None, None, Some(format!("{e:?}")), Some(e.to_string())),
+            };
+            Ok(Box::new(move |report: &mut ReportData| {
+                report.cargo_updates = cargo_updates;
+                report.cargo_updates_git = cargo_updates_git;
+                report.cargo_update_check_error_debug = cargo_update_check_error_debug;
+                report.cargo_update_check_error_display = cargo_update_check_error_display;
+            }) as Section)
+        })
+    }
+}
+
+/// Checks whether there are pending system updates.
+pub struct CronApt;
+
+impl Collector for CronApt {
+    fn name(&self) -> &'static str { "cronApt" }
+
+    fn applies_to(&self, os_type: os_info::Type) -> bool {
+        // NixOS updates are configured to be installed automatically, see NixosUpgradeStatus
+        !matches!(os_type, os_info::Type::NixOS)
+    }
+
+    fn collect<'a>(&'a self, #[cfg_attr(windows, allow(unused))] ctx: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            #[cfg(unix)] let cron_apt = ctx.config.root && {
+                // not NixOS, assume Debian
+                let syslogs = [Path::new("/var/log/syslog"), Path::new("/var/log/syslog.1")];
+                let use_journal = match ctx.config.cron_apt.log {
+                    CronAptLog::Auto => !syslogs.iter().any(|log_path| log_path.exists()),
+                    CronAptLog::Syslog => false,
+                    CronAptLog::Journald => true,
+                };
+                let mut cron_apt = None;
+                if use_journal {
+                    let mut command = Command::new("journalctl");
+                    command.arg("--output=json");
+                    command.arg("--quiet");
+                    command.arg("--reverse");
+                    command.arg("--identifier=cron-apt");
+                    command.arg(format!("--since={}", ctx.config.cron_apt.since));
+                    let output = check_with_timeout("journalctl", Duration::from_secs(ctx.config.timeouts.journalctl), &mut command).await??;
+                    let mut messages = Vec::default();
+                    for line in String::from_utf8(output.stdout)?.lines() {
+                        let entry = serde_json::from_str::<JournalEntry>(line).map_err(|_| Error::JournalJson(line.to_owned()))?;
+                        let message = match entry.message {
+                            Some(JournalField::Text(message)) => message,
+                            Some(JournalField::Binary(message)) => String::from_utf8_lossy(&message).into_owned(),
+                            None => continue,
+                        };
+                        // match the format of syslog lines
+                        messages.push(format!("cron-apt: {message}"));
+                    }
+                    cron_apt = cron_apt_status(messages);
+                } else {
+                    for log_path in syslogs {
+                        if log_path.exists() {
+                            let log_f = BufReader::new(File::open(log_path).await?);
+                            cron_apt = cron_apt_status(LinesStream::new(log_f.lines()).try_collect::<Vec<_>>().await.at(log_path)?.into_iter().rev());
+                            if cron_apt.is_some() { break }
+                        }
+                    }
+                }
+                cron_apt.unwrap_or(true)
+            };
+            #[cfg(windows)] let cron_apt = true; // see night-windows-service crate in private night repo for a way to actually check for updates
+            Ok(Box::new(move |report: &mut ReportData| report.cron_apt = cron_apt) as Section)
+        })
+    }
+}
+
+#[cfg(unix)]
+#[derive(Deserialize)]
+struct JournalEntry {
+    #[serde(rename = "MESSAGE")]
+    message: Option<JournalField>,
+}
+
+/// journalctl's JSON output represents fields which aren't valid UTF-8 as arrays of bytes.
+#[cfg(unix)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum JournalField {
+    Text(String),
+    Binary(Vec<u8>),
+}
+
+/// Determines from cron-apt's log lines, given newest first, whether it has downloaded updates which are not yet installed.
+/// Returns `None` if the lines don't include a cron-apt result.
+#[cfg(unix)]
+fn cron_apt_status(lines: impl IntoIterator<Item = String>) -> Option<bool> {
+    for line in lines {
+        if line.contains("cron-apt: Download complete and in download only mode") {
+            return Some(true)
+        } else if line.contains("cron-apt: 0 upgraded, 0 newly installed, 0 to remove and 0 not upgraded.") {
+            return Some(false)
+        }
+    }
+    None
+}
+
+/// Checks disk space and inodes of the configured file systems, running the configured cleanup actions if disk space is low.
+pub struct DiskSpace;
+
+impl Collector for DiskSpace {
+    fn name(&self) -> &'static str { "diskspace" }
+
+    // installing updates uses disk space, which would be counted against the space reclaimed by cleanup
+    fn run_last(&self) -> bool { true }
+
+    fn collect<'a>(&'a self, ctx: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let sys = System::new();
+            let mut filesystems = file_system_usage(&sys, ctx.config)?;
+            let mut cleanup = Vec::default();
+            if filesystems.values().any(|fs| ctx.config.cleanup.is_low(fs)) {
+                // a file system mounted at multiple configured paths should only count once towards reclaimed space
+                let devices = ctx.config.file_systems.iter()
+                    .map(|path| Ok::<_, Error>((path.clone(), sys.mount_at(path).at(path)?.fs_mounted_from)))
+                    .process_results(|devices| devices.collect::<HashMap<_, _>>())?;
+                for action in &ctx.config.cleanup.actions {
+                    if ctx.dry_run {
+                        if ctx.verbose { println!("low on disk space, would run {}", action.name()) }
+                        cleanup.push(CleanupResult {
+                            action: action.name().to_owned(),
+                            reclaimed: 0,
+                            error: None,
+                            planned: true,
+                        });
+                        continue
+                    }
+                    if ctx.verbose { println!("low on disk space, running {}", action.name()) }
+                    let result = run_cleanup_action(action, ctx.config).await;
+                    let new_filesystems = file_system_usage(&sys, ctx.config)?;
+                    cleanup.push(CleanupResult {
+                        action: action.name().to_owned(),
+                        reclaimed: new_filesystems.iter()
+                            .unique_by(|(path, _)| &devices[*path])
+                            .map(|(path, fs)| fs.diskspace_free.saturating_sub(filesystems[path].diskspace_free))
+                            .sum(),
+                        error: result.err().map(|e| e.to_string()),
+                        planned: false,
+                    });
+                    filesystems = new_filesystems;
+                }
+            }
+            let fs = filesystems.values()
+                .min_by(|fs1, fs2| fs1.free_ratio().total_cmp(&fs2.free_ratio()))
+                .ok_or(Error::NoFileSystems)?
+                .clone();
+            Ok(Box::new(move |report: &mut ReportData| {
+                report.diskspace_total = fs.diskspace_total;
+                report.diskspace_free = fs.diskspace_free;
+                report.inodes_total = fs.inodes_total;
+                report.inodes_free = fs.inodes_free;
+                report.filesystems = filesystems;
+                report.cleanup = cleanup;
+            }) as Section)
+        })
+    }
+}
+
+fn file_system_usage(sys: &System, config: &Config) -> Result<HashMap<String, FileSystemUsage>, Error> {
+    config.file_systems.iter()
+        .map(|path| Ok::<_, Error>((path.clone(), FileSystemUsage::new(&sys.mount_at(path).at(path)?)?)))
+        .process_results(|fss| fss.collect())
+}
+
+async fn run_cleanup_action(action: &CleanupAction, config: &Config) -> Result<(), Error> {
+    let timeout = Duration::from_secs(config.timeouts.cleanup);
+    match action {
+        CleanupAction::CargoSweep { paths } => for path in paths {
+            let mut command = config.cargo.command(config.root);
+            command.arg("sweep");
+            command.arg("-ir");
+            command.arg(path);
+            check_with_timeout("cargo sweep", timeout, &mut command).await??;
+        },
+        #[cfg(unix)] CleanupAction::AptClean => {
+            check_with_timeout("apt-get clean", timeout, Command::new("apt-get").arg("clean")).await??;
+        }
+        #[cfg(unix)] CleanupAction::NixCollectGarbage { delete_older_than } => {
+            check_with_timeout("nix-collect-garbage", timeout, Command::new("nix-collect-garbage").arg("--delete-older-than").arg(delete_older_than)).await??;
+        }
+        #[cfg(unix)] CleanupAction::JournalVacuum { size, time } => {
+            // journalctl without vacuum options would print the entire journal
+            if size.is_none() && time.is_none() { return Err(Error::JournalVacuumUnbounded) }
+            let mut command = Command::new("journalctl");
+            if let Some(size) = size {
+                command.arg(format!("--vacuum-size={size}"));
+            }
+            if let Some(time) = time {
+                command.arg(format!("--vacuum-time={time}"));
+            }
+            check_with_timeout("journalctl --vacuum", timeout, &mut command).await??;
+        }
+    }
+    Ok(())
+}
+
+/// Checks whether a reboot is required, emulating `NEEDRESTART-KSTA` codes on systems without needrestart.
+pub struct NeedRestart;
+
+impl Collector for NeedRestart {
+    fn name(&self) -> &'static str { "needrestart" }
+
+    fn collect<'a>(&'a self, #[cfg_attr(windows, allow(unused))] ctx: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            #[cfg(unix)] let (needrestart, reboot_reasons, needrestart_details) = match ctx.os_info.os_type() {
+                os_info::Type::Macos => (Some(1), Vec::default(), None), // update workflow includes reboot
+                os_info::Type::NixOS => if ctx.config.root {
+                    let output = output_with_timeout("nixos-needsreboot", Duration::from_secs(ctx.config.timeouts.nixos_needsreboot), &mut Command::new("nixos-needsreboot")).await??;
+                    let stderr = String::from_utf8_lossy(&output.stderr);
+                    match output.status.code() {
+                        Some(0) => (Some(1), Vec::default(), None), // no reboot needed
+                        Some(2) => { // reboot needed
+                            let reboot_reasons = String::from_utf8_lossy(&output.stdout).lines()
+                                .filter_map(reboot_reason)
+                                .collect::<Vec<_>>();
+                            // new kernel version, see NEEDRESTART-KSTA codes
+                            let needrestart = if reboot_reasons.iter().any(|reason| matches!(reason.component, RebootComponent::Kernel)) { 3 } else { 2 };
+                            (Some(needrestart), reboot_reasons, None)
+                        }
+                        Some(1) if regex_is_match!("nixos-needsreboot: I/O error at /nix/store/.+/lib/modules: No such file or directory \\(os error 2\\)", &stderr) => (Some(3), vec![RebootReason { // NixOS seems to delete old kernel modules after upgrade
+                            component: RebootComponent::Kernel,
+                            raw: stderr.trim().to_owned(),
+                        }], None),
+                        code => {
+                            if let Some(code) = code {
+                                eprintln!("nixos-needsreboot exited with status code {code}");
+                            } else {
+                                eprintln!("nixos-needsreboot exited with no status code");
+                            }
+                            (Some(0), Vec::default(), None) // unknown status
+                        }
+                    }
+                } else {
+                    (None, Vec::default(), None)
+                },
+                _ => if ctx.config.root {
+                    let details = needrestart_details(&String::from_utf8(output_with_timeout("needrestart", Duration::from_secs(ctx.config.timeouts.needrestart), Command::new("/usr/sbin/needrestart").arg("-b")).await??.stdout)?)?;
+                    (details.kernel_status, Vec::default(), Some(details))
+                } else {
+                    (None, Vec::default(), None)
+                },
+            };
+            #[cfg(windows)] let (needrestart, reboot_reasons, needrestart_details) = (Some(2), Vec::default(), None); // see night-windows-service crate in private night repo for a way to actually check for updates
+            Ok(Box::new(move |report: &mut ReportData| {
+                report.needrestart = needrestart;
+                report.needrestart_details = needrestart_details;
+                report.reboot_reasons = reboot_reasons;
+            }) as Section)
+        })
+    }
+}
+
+/// Parses the output of `needrestart -b`.
+#[cfg(unix)]
+fn needrestart_details(output: &str) -> Result<NeedRestartDetails, ParseIntError> {
+    let mut details = NeedRestartDetails::default();
+    for line in output.lines() {
+        let Some((key, value)) = line.split_once(':') else { continue };
+        let value = value.trim();
+        match key {
+            "NEEDRESTART-KCUR" => details.kernel_current = Some(value.to_owned()),
+            "NEEDRESTART-KEXP" => details.kernel_expected = Some(value.to_owned()),
+            "NEEDRESTART-KSTA" => details.kernel_status = Some(value.parse()?),
+            "NEEDRESTART-UCSTA" => details.microcode_status = Some(value.parse()?),
+            "NEEDRESTART-UCCUR" => details.microcode_current = Some(value.to_owned()),
+            "NEEDRESTART-UCEXP" => details.microcode_expected = Some(value.to_owned()),
+            "NEEDRESTART-SVC" => details.services.push(value.to_owned()),
+            "NEEDRESTART-CONT" => details.containers.push(value.to_owned()),
+            "NEEDRESTART-SESS" => details.sessions.push(value.to_owned()),
+            _ => {}
+        }
+    }
+    Ok(details)
+}
+
+/// Parses a line of `nixos-needsreboot` output, which lists each changed component as `<component>: <booted version> -> <new version>`.
+/// Returns `None` for other lines, such as headers or warnings.
+#[cfg(unix)]
+fn reboot_reason(line: &str) -> Option<RebootReason> {
+    let line = line.trim();
+    let (_, component, _, _) = regex_captures!("^([A-Za-z0-9_.-]+): (.+) -> (.+)$", line)?;
+    Some(RebootReason {
+        component: match component {
+            "linux" | "kernel" | "kernel-modules" => RebootComponent::Kernel,
+            "initrd" => RebootComponent::Initrd,
+            "systemd" => RebootComponent::Systemd,
+            _ => RebootComponent::Other,
+        },
+        raw: line.to_owned(),
+    })
+}
+
+/// Checks whether the last automatic upgrade on NixOS succeeded.
+#[cfg(unix)]
+pub struct NixosUpgradeStatus;
+
+#[cfg(unix)]
+impl Collector for NixosUpgradeStatus {
+    fn name(&self) -> &'static str { "nixosUpgrade" }
+
+    fn applies_to(&self, os_type: os_info::Type) -> bool {
+        matches!(os_type, os_info::Type::NixOS)
+    }
+
+    fn collect<'a>(&'a self, ctx: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let mut command = Command::new("systemctl");
+            command.arg("show");
+            command.arg("--property=LoadState,Result,ExecMainStartTimestamp,ExecMainStatus");
+            command.arg("--timestamp=unix");
+            command.arg("nixos-upgrade.service");
+            let output = check_with_timeout("systemctl show", Duration::from_secs(ctx.config.timeouts.systemctl), &mut command).await??;
+            let mut loaded = false;
+            let mut last_run = None;
+            let mut result = String::default();
+            let mut exit_status = None;
+            for line in String::from_utf8(output.stdout)?.lines() {
+                let Some((key, value)) = line.split_once('=') else { continue };
+                match key {
+                    "LoadState" => loaded = value == "loaded",
+                    "Result" => result = value.to_owned(),
+                    "ExecMainStartTimestamp" => last_run = value.strip_prefix('@').map(|timestamp| timestamp.parse()).transpose()?,
+                    "ExecMainStatus" => exit_status = Some(value.parse()?),
+                    _ => {}
+                }
+            }
+            let nixos_upgrade = loaded.then(|| NixosUpgrade {
+                // exit status is reported as 0 if the service hasn't run
+                exit_status: exit_status.filter(|_| last_run.is_some()),
+                last_run, result,
+            });
+            let cron_apt = ctx.config.root && nixos_upgrade.as_ref().is_some_and(|nixos_upgrade| nixos_upgrade.result != "success");
+            Ok(Box::new(move |report: &mut ReportData| {
+                report.cron_apt = cron_apt;
+                report.nixos_upgrade = nixos_upgrade;
+            }) as Section)
+        })
+    }
+}
+
+/// Checks for marker files indicating obsolete configuration files left behind by package upgrades.
+#[cfg(unix)]
+pub struct OldConfFilesCheck;
+
+#[cfg(unix)]
+impl Collector for OldConfFilesCheck {
+    fn name(&self) -> &'static str { "oldconffiles" }
+
+    fn collect<'a>(&'a self, ctx: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let oldconffiles = ctx.config.oldconffiles.iter()
+                .map(|entry| (entry.name().to_owned(), entry.path().exists()))
+                .collect();
+            Ok(Box::new(move |report: &mut ReportData| report.oldconffiles = oldconffiles) as Section)
+        })
+    }
+}
+
+/// Reports a more accurate Debian version than `os_info`, which only reports the major version.
+#[cfg(unix)]
+pub struct OsVersion;
+
+#[cfg(unix)]
+impl Collector for OsVersion {
+    fn name(&self) -> &'static str { "osVersion" }
+
+    fn applies_to(&self, os_type: os_info::Type) -> bool {
+        matches!(os_type, os_info::Type::Debian)
+    }
+
+    fn collect<'a>(&'a self, _: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let [major, minor, patch] = fs::read_to_string("/etc/debian_version").await?.trim_end().split('.').map(u64::from_str).chain(iter::repeat(Ok(0))).next_array().expect("iter::repeat produces an infinite iterator");
+            let os_version = os_info::Version::Semantic(major?, minor?, patch?);
+            Ok(Box::new(move |report: &mut ReportData| report.os_version = os_version) as Section)
+        })
+    }
+}
+
+/// Installs updates for packages installed via Scoop and reports those that could not be updated. In dry-run mode, only reports pending updates.
+#[cfg(windows)]
+pub struct ScoopUpdates;
+
+#[cfg(windows)]
+impl Collector for ScoopUpdates {
+    fn name(&self) -> &'static str { "scoop" }
+
+    fn collect<'a>(&'a self, ctx: &'a Context<'a>) -> Pin<Box<dyn Future<Output = Result<Section, Error>> + Send + 'a>> {
+        Box::pin(async move {
+            let timeout = Duration::from_secs(ctx.config.timeouts.scoop);
+            if !ctx.dry_run {
+                check_with_timeout("scoop update", timeout, Command::new("powershell").arg("-Command").arg("scoop update").release_create_no_window()).await??;
+                check_with_timeout("scoop update --all", timeout, Command::new("powershell").arg("-Command").arg("scoop update --all").release_create_no_window()).await??;
+            }
+            let stdout = check_with_timeout("scoop status | ConvertTo-Json", timeout, Command::new("powershell").arg("-Command").arg("scoop status | ConvertTo-Json").release_create_no_window()).await??.stdout;
+            let scoop_updates = if stdout == b"Scoop is up to date.\nEverything is ok!\n" {
+                Vec::default()
+            } else {
+                (0..stdout.len())
+                    .find_map(|idx| serde_json::from_slice::<serde_with::de::DeserializeAsWrap<Vec<ScoopUpdate>, serde_with::OneOrMany<serde_with::Same>>>(&stdout[idx..]).ok())
+                    .ok_or_else(|| Error::ScoopJson(String::from_utf8_lossy(&stdout).into_owned()))?
+                    .into_inner()
+            };
+            Ok(Box::new(move |report: &mut ReportData| report.scoop_updates = scoop_updates) as Section)
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use {
+        crate::RebootComponent,
+        super::*,
+    };
+
+    #[test]
+    fn reboot_reasons() {
+        let output = "\
+Reboot is required for the following reasons:
+linux: 6.6.30 -> 6.6.31
+initrd: 6.6.30 -> 6.6.31
+systemd: 255.4 -> 255.6
+glibc: 2.39-52 -> 2.39-58
+warning: could not determine the version of kernel-modules
+";
+        let reasons = output.lines().filter_map(reboot_reason).collect::<Vec<_>>();
+        assert_eq!(reasons.len(), 4);
+        assert!(matches!(reasons[0].component, RebootComponent::Kernel));
+        assert_eq!(reasons[0].raw, "linux: 6.6.30 -> 6.6.31");
+        assert!(matches!(reasons[1].component, RebootComponent::Initrd));
+        assert!(matches!(reasons[2].component, RebootComponent::Systemd));
+        assert!(matches!(reasons[3].component, RebootComponent::Other));
+    }
+
+    #[test]
+    fn needrestart_batch_output() {
+        let output = "\
+NEEDRESTART-VER: 3.6
+NEEDRESTART-KCUR: 6.1.0-13-amd64
+NEEDRESTART-KEXP: 6.1.0-18-amd64
+NEEDRESTART-KSTA: 3
+NEEDRESTART-UCSTA: 1
+NEEDRESTART-UCCUR: 0x000000f4
+NEEDRESTART-UCEXP: 0x000000f4
+NEEDRESTART-SVC: cron.service
+NEEDRESTART-SVC: ssh.service
+";
+        let details = needrestart_details(output).unwrap();
+        assert_eq!(details.kernel_current.as_deref(), Some("6.1.0-13-amd64"));
+        assert_eq!(details.kernel_expected.as_deref(), Some("6.1.0-18-amd64"));
+        assert_eq!(details.kernel_status, Some(3));
+        assert_eq!(details.microcode_status, Some(1));
+        assert_eq!(details.microcode_current.as_deref(), Some("0x000000f4"));
+        assert_eq!(details.services, ["cron.service", "ssh.service"]);
+        assert!(details.containers.is_empty());
+    }
+
+    #[test]
+    fn needrestart_nothing_pending() {
+        let output = "\
+NEEDRESTART-VER: 3.6
+NEEDRESTART-KCUR: 6.1.0-18-amd64
+NEEDRESTART-KEXP: 6.1.0-18-amd64
+NEEDRESTART-KSTA: 1
+";
+        let details = needrestart_details(output).unwrap();
+        assert_eq!(details.kernel_status, Some(1));
+        assert!(details.services.is_empty());
+    }
+}
@@ -0,0 +1,474 @@
+//! The cronjob wrapper mode, which runs a command and reports its result.
+
+use {
+    std::{
+        collections::VecDeque,
+        ffi::OsString,
+        fs::{
+            OpenOptions,
+            TryLockError,
+        },
+        io,
+        pin::pin,
+        process::Stdio,
+        time::{
+            Duration,
+            Instant,
+            SystemTime,
+        },
+    },
+    futures::future,
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    tokio::{
+        io::{
+            AsyncRead,
+            AsyncReadExt as _,
+            AsyncWrite,
+            AsyncWriteExt as _,
+        },
+        process::Command,
+    },
+    wheel::traits::IoResultExt as _,
+    night_device_report::{
+        Config,
+        Error,
+        lock_dir,
+        terminate,
+    },
+    crate::Submission,
+};
+#[cfg(unix)] use std::os::unix::process::ExitStatusExt as _;
+#[cfg(target_os = "linux")] use nix::sys::{
+    resource::{
+        Usage,
+        UsageWho,
+        getrusage,
+    },
+    time::TimeValLike as _,
+};
+
+/// Exit status of the wrapper if the command was terminated because it exceeded the `--timeout`, matching GNU `timeout`.
+const EXIT_TIMED_OUT: i32 = 124;
+/// Exit status of the wrapper if the cronjob could not be run or reported for reasons other than the command failing to start.
+pub(crate) const EXIT_WRAPPER_FAILED: i32 = 125;
+/// Exit status of the wrapper if the command was found but could not be started.
+const EXIT_CANNOT_EXECUTE: i32 = 126;
+/// Exit status of the wrapper if the command could not be found.
+const EXIT_NOT_FOUND: i32 = 127;
+
+/// How long to keep reading the command's output after it exits.
+const OUTPUT_DRAIN_PERIOD: Duration = Duration::from_secs(5);
+
+#[derive(clap::Args)]
+pub(crate) struct CronArgs {
+    /// How many bytes of the end of the cronjob's stdout and stderr to include in the report.
+    #[clap(long, default_value_t = 4096, requires = "cronjob")]
+    tail_bytes: usize,
+    /// Terminate the cronjob if it's still running after this many seconds.
+    #[clap(long, requires = "cronjob")]
+    timeout: Option<u64>,
+    /// How many seconds to wait for the cronjob to exit after sending SIGTERM on timeout before sending SIGKILL.
+    #[clap(long, default_value_t = 10, requires = "timeout")]
+    grace_period: u64,
+    /// What to do if the previous run of the same cronjob is still running.
+    #[clap(long, value_enum, default_value_t = OnOverlap::Report, requires = "cronjob")]
+    on_overlap: OnOverlap,
+    /// While the cronjob is running, send a report with the `running` state every this many seconds.
+    #[clap(long, requires = "cronjob", value_parser = clap::value_parser!(u64).range(1..))]
+    heartbeat: Option<u64>,
+    /// How many times to run the cronjob again if it fails. Only the last attempt's output is included in the report.
+    #[clap(long, default_value_t = 0, requires = "cronjob")]
+    retries: usize,
+    /// How many seconds to wait before the first retry. The delay is doubled for each subsequent retry.
+    #[clap(long, default_value_t = 60, requires = "retries")]
+    retry_delay: u64,
+    /// Don't print the cronjob's output unless it fails, like cronic.
+    #[clap(long, requires = "cronjob")]
+    cronic: bool,
+    /// With --cronic, how many bytes of the end of the cronjob's stdout and stderr to keep for printing on failure.
+    #[clap(long, default_value_t = 1024 * 1024, requires = "cronic")]
+    cronic_max_bytes: usize,
+}
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+enum OnOverlap {
+    /// Exit without running the command or sending a report.
+    Skip,
+    /// Wait for the previous run to finish, then run the command.
+    Wait,
+    /// Send a report with the `overlapping` state instead of running the command.
+    Report,
+}
+
+#[derive(Default, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+enum CronState {
+    /// The command exited on its own.
+    #[default]
+    Exited,
+    /// The command was terminated because it exceeded the `--timeout`.
+    TimedOut,
+    /// The command could not be started, e.g. because it doesn't exist.
+    SpawnFailed,
+    /// The command was not run because the previous run of the same cronjob was still running.
+    Overlapping,
+    /// The command is about to be started. Sent before the final report.
+    Started,
+    /// The command is still running. Sent periodically if `--heartbeat` is specified.
+    Running,
+}
+
+/// Resource usage of the command, including any subprocesses it waited for. Only collected on Linux.
+#[derive(Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct ResourceUsage {
+    user_time_ms: u64,
+    system_time_ms: u64,
+    /// The peak resident set size, in KiB.
+    /// The kernel only tracks this as a maximum over all children of the wrapper, so for retries, this is only known if it exceeds the peak of all earlier attempts, and `None` otherwise.
+    max_rss_kib: Option<u64>,
+    block_reads: u64,
+    block_writes: u64,
+}
+
+#[cfg(target_os = "linux")]
+impl ResourceUsage {
+    /// The resource usage of the children which were waited for between the two calls to `getrusage(RUSAGE_CHILDREN)`.
+    fn between(before: &Usage, after: &Usage) -> Self {
+        Self {
+            user_time_ms: (after.user_time() - before.user_time()).num_milliseconds() as u64,
+            system_time_ms: (after.system_time() - before.system_time()).num_milliseconds() as u64,
+            // if the maximum didn't increase, this child's peak is somewhere below it
+            max_rss_kib: (after.max_rss() > before.max_rss()).then_some(after.max_rss() as u64),
+            block_reads: (after.block_reads() - before.block_reads()) as u64,
+            block_writes: (after.block_writes() - before.block_writes()) as u64,
+        }
+    }
+}
+
+/// The result of one run of the command when using `--retries`.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct CronAttempt {
+    state: CronState,
+    status: Option<i32>,
+    signal: Option<i32>,
+    core_dumped: bool,
+    error: Option<String>,
+    start_time: u64,
+    end_time: u64,
+    duration_ms: u64,
+    resource_usage: Option<ResourceUsage>,
+}
+
+impl CronAttempt {
+    fn new(report: &CronReport) -> Self {
+        Self {
+            state: report.state,
+            status: report.status,
+            signal: report.signal,
+            core_dumped: report.core_dumped,
+            error: report.error.clone(),
+            start_time: report.start_time,
+            end_time: report.end_time,
+            duration_ms: report.duration_ms,
+            resource_usage: report.resource_usage,
+        }
+    }
+}
+
+/// With `--retries`, the fields describe the last attempt, except for the timestamps and duration, which cover all attempts.
+#[derive(Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct CronReport {
+    key: String,
+    #[serde(default)]
+    state: CronState,
+    status: Option<i32>,
+    /// The signal which terminated the command, if any. Always `None` on Windows.
+    #[serde(default)]
+    signal: Option<i32>,
+    #[serde(default)]
+    core_dumped: bool,
+    /// Why the command could not be started.
+    #[serde(default)]
+    error: Option<String>,
+    /// When the command was started, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    start_time: u64,
+    /// When the command exited, or when the report was sent if it hasn't exited, in milliseconds since the Unix epoch.
+    #[serde(default)]
+    end_time: u64,
+    #[serde(default)]
+    duration_ms: u64,
+    #[serde(default)]
+    stdout_tail: String,
+    #[serde(default)]
+    stderr_tail: String,
+    #[serde(default)]
+    resource_usage: Option<ResourceUsage>,
+    /// Every run of the command, in order. Empty if the command wasn't run.
+    #[serde(default)]
+    attempts: Vec<CronAttempt>,
+    /// Only used to determine the exit status of the wrapper, not sent to the server.
+    #[serde(skip)]
+    spawn_error_kind: Option<io::ErrorKind>,
+}
+
+impl CronReport {
+    /// A report for a command which hasn't exited, either because it wasn't run or because it's still running.
+    fn without_status(config: &Config, state: CronState, error: Option<String>, start_time: SystemTime, start: Instant) -> Self {
+        Self {
+            key: config.device_key.clone(),
+            status: None,
+            signal: None,
+            core_dumped: false,
+            start_time: unix_millis(start_time),
+            end_time: unix_millis(SystemTime::now()),
+            duration_ms: start.elapsed().as_millis() as u64,
+            stdout_tail: String::default(),
+            stderr_tail: String::default(),
+            resource_usage: None,
+            attempts: Vec::default(),
+            spawn_error_kind: None,
+            state, error,
+        }
+    }
+
+    /// The exit status the wrapper should exit with after this report has been sent.
+    pub(crate) fn exit_code(&self) -> i32 {
+        match self.state {
+            CronState::Exited => if let Some(status) = self.status {
+                status
+            } else if let Some(signal) = self.signal {
+                128 + signal
+            } else {
+                1
+            },
+            CronState::TimedOut => EXIT_TIMED_OUT,
+            CronState::SpawnFailed => if self.spawn_error_kind == Some(io::ErrorKind::NotFound) { EXIT_NOT_FOUND } else { EXIT_CANNOT_EXECUTE },
+            // the previous run's report covers the failure if there is one
+            CronState::Overlapping => 0,
+            CronState::Started | CronState::Running => unreachable!("progress reports are never final"),
+        }
+    }
+}
+
+fn unix_millis(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH).expect("system clock is before 1970").as_millis() as u64
+}
+
+/// The end of a stream of the command's output.
+#[derive(Default)]
+struct Tail {
+    buf: VecDeque<u8>,
+    /// How many bytes were dropped from the start of the output.
+    omitted: usize,
+}
+
+impl Tail {
+    /// The last `limit` bytes of the output.
+    fn last(&mut self, limit: usize) -> String {
+        let buf = self.buf.make_contiguous();
+        String::from_utf8_lossy(&buf[buf.len().saturating_sub(limit)..]).into_owned()
+    }
+
+    /// All kept output, with a note if the start of the output was dropped.
+    fn into_output(mut self) -> Vec<u8> {
+        let mut output = Vec::default();
+        if self.omitted > 0 {
+            output.extend(format!("[{} bytes of output omitted]\n", self.omitted).bytes());
+        }
+        output.extend_from_slice(self.buf.make_contiguous());
+        output
+    }
+}
+
+/// Copies the command's output to `writer`, keeping the last `limit` bytes of it in `tail`.
+async fn pump(mut reader: impl AsyncRead + Unpin, mut writer: impl AsyncWrite + Unpin, limit: usize, tail: &mut Tail) -> io::Result<()> {
+    let mut buf = [0; 8192];
+    loop {
+        let n = reader.read(&mut buf).await?;
+        if n == 0 { break }
+        writer.write_all(&buf[..n]).await?;
+        writer.flush().await?;
+        tail.buf.extend(&buf[..n]);
+        if tail.buf.len() > limit {
+            let excess = tail.buf.len() - limit;
+            tail.buf.drain(..excess);
+            tail.omitted += excess;
+        }
+    }
+    Ok(())
+}
+
+/// Sends a report about a cronjob which hasn't exited yet. These aren't spooled since they're superseded by the final report.
+async fn ping(client: &reqwest::Client, config: &Config, cronjob: &str, quiet: bool, data: CronReport) {
+    if let Err(e) = (Submission::Cronjob { cronjob: cronjob.to_owned(), data }).send(client, config).await {
+        if !quiet { eprintln!("failed to send progress report for {cronjob}: {e}") }
+    }
+}
+
+/// Runs the cronjob's command once, forwarding its output. With `--cronic`, the end of the output is returned instead.
+async fn attempt(config: &Config, cmd: &OsString, cmd_args: &[OsString], args: &CronArgs) -> Result<(CronReport, Vec<u8>, Vec<u8>), Error> {
+    let cmd_name = cmd.to_string_lossy().into_owned();
+    let start_time = SystemTime::now();
+    let start = Instant::now();
+    let mut command = Command::new(cmd);
+    command.args(cmd_args);
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    #[cfg(unix)] if args.timeout.is_some() {
+        // allows terminating any subprocesses along with the command
+        command.process_group(0);
+    }
+    // wait4 would require reaping the child ourselves, so the difference in the usage of all waited-for children is used instead
+    #[cfg(target_os = "linux")] let usage_before = getrusage(UsageWho::RUSAGE_CHILDREN);
+    let mut child = match command.spawn() {
+        Ok(child) => child,
+        Err(e) => {
+            eprintln!("failed to start {cmd_name}: {e}");
+            return Ok((CronReport {
+                spawn_error_kind: Some(e.kind()),
+                ..CronReport::without_status(config, CronState::SpawnFailed, Some(e.to_string()), start_time, start)
+            }, Vec::default(), Vec::default()))
+        }
+    };
+    let stdout = child.stdout.take().expect("stdout is piped");
+    let stderr = child.stderr.take().expect("stderr is piped");
+    let (stdout_writer, stderr_writer, limit): (Box<dyn AsyncWrite + Unpin + Send>, Box<dyn AsyncWrite + Unpin + Send>, _) = if args.cronic {
+        // the output is kept in the tails instead, to be printed if the command fails
+        (Box::new(tokio::io::sink()), Box::new(tokio::io::sink()), args.tail_bytes.max(args.cronic_max_bytes))
+    } else {
+        (Box::new(tokio::io::stdout()), Box::new(tokio::io::stderr()), args.tail_bytes)
+    };
+    let wait = async {
+        if let Some(timeout) = args.timeout {
+            match tokio::time::timeout(Duration::from_secs(timeout), child.wait()).await {
+                Ok(status) => Ok((CronState::Exited, status?)),
+                Err(_) => Ok((CronState::TimedOut, terminate(&mut child, Duration::from_secs(args.grace_period)).await?)),
+            }
+        } else {
+            Ok((CronState::Exited, child.wait().await?))
+        }
+    };
+    let mut stdout_tail = Tail::default();
+    let mut stderr_tail = Tail::default();
+    let (state, status) = {
+        let mut wait = pin!(wait);
+        let mut pumps = pin!(future::try_join(
+            pump(stdout, stdout_writer, limit, &mut stdout_tail),
+            pump(stderr, stderr_writer, limit, &mut stderr_tail),
+        ));
+        let mut pumped = false;
+        let (state, status) = loop {
+            tokio::select! {
+                result = &mut pumps, if !pumped => {
+                    result.at_command(cmd_name.clone())?;
+                    pumped = true;
+                }
+                result = &mut wait => break result.at_command(cmd_name.clone())?,
+            }
+        };
+        if !pumped {
+            // background processes started by the command may keep the output pipes open indefinitely, so only wait a little for remaining output
+            if let Ok(result) = tokio::time::timeout(OUTPUT_DRAIN_PERIOD, pumps).await {
+                result.at_command(cmd_name)?;
+            }
+        }
+        (state, status)
+    };
+    #[cfg(unix)] let (signal, core_dumped) = (status.signal(), status.core_dumped());
+    #[cfg(windows)] let (signal, core_dumped) = (None, false);
+    #[cfg(target_os = "linux")] let resource_usage = usage_before.and_then(|before| Ok(ResourceUsage::between(&before, &getrusage(UsageWho::RUSAGE_CHILDREN)?))).ok();
+    #[cfg(not(target_os = "linux"))] let resource_usage = None;
+    let report = CronReport {
+        key: config.device_key.clone(),
+        status: status.code(),
+        error: None,
+        state, signal, core_dumped,
+        start_time: unix_millis(start_time),
+        end_time: unix_millis(SystemTime::now()),
+        duration_ms: start.elapsed().as_millis() as u64,
+        attempts: Vec::default(),
+        spawn_error_kind: None,
+        stdout_tail: stdout_tail.last(args.tail_bytes),
+        stderr_tail: stderr_tail.last(args.tail_bytes),
+        resource_usage,
+    };
+    Ok(if args.cronic {
+        (report, stdout_tail.into_output(), stderr_tail.into_output())
+    } else {
+        (report, Vec::default(), Vec::default())
+    })
+}
+
+/// Runs the cronjob's command, retrying if requested, and forwarding its output (or with `--cronic`, printing it only if the command fails). Returns `None` if the run was skipped due to `--on-overlap=skip`.
+pub(crate) async fn run(client: &reqwest::Client, config: &Config, quiet: bool, cronjob: &str, cmd: OsString, cmd_args: Vec<OsString>, args: &CronArgs) -> Result<Option<CronReport>, Error> {
+    let start_time = SystemTime::now();
+    let start = Instant::now();
+    let lock_path = lock_dir().await?.join(format!("{}.lock", cronjob.replace(['/', '\\'], "_")));
+    // the lock is released when this file is closed at the end of this function
+    let lock = OpenOptions::new().create(true).write(true).truncate(false).open(&lock_path).at(&lock_path)?;
+    match lock.try_lock() {
+        Ok(()) => {}
+        Err(TryLockError::WouldBlock) => match args.on_overlap {
+            OnOverlap::Skip => return Ok(None),
+            OnOverlap::Wait => {
+                let lock = lock.try_clone().at(&lock_path)?;
+                tokio::task::spawn_blocking(move || lock.lock()).await?.at(&lock_path)?;
+            }
+            OnOverlap::Report => return Ok(Some(CronReport::without_status(config, CronState::Overlapping, None, start_time, start))),
+        },
+        Err(TryLockError::Error(e)) => Err(e).at(&lock_path)?,
+    }
+    let started = ping(client, config, cronjob, quiet, CronReport::without_status(config, CronState::Started, None, start_time, start));
+    let attempts = async {
+        let mut attempts = Vec::default();
+        let mut retry_delay = Duration::from_secs(args.retry_delay);
+        loop {
+            let (mut report, stdout, stderr) = attempt(config, &cmd, &cmd_args, args).await?;
+            attempts.push(CronAttempt::new(&report));
+            let failed = report.exit_code() != 0;
+            // a command which can't be started won't start on retry either
+            if failed && !matches!(report.state, CronState::SpawnFailed) && attempts.len() <= args.retries {
+                if !quiet && !args.cronic { eprintln!("{} failed, retrying in {retry_delay:?}", cmd.to_string_lossy()) }
+                tokio::time::sleep(retry_delay).await;
+                retry_delay *= 2;
+                continue
+            }
+            if args.cronic && failed {
+                let mut stdout_writer = tokio::io::stdout();
+                stdout_writer.write_all(&stdout).await.at_unknown()?;
+                stdout_writer.flush().await.at_unknown()?;
+                let mut stderr_writer = tokio::io::stderr();
+                stderr_writer.write_all(&stderr).await.at_unknown()?;
+                stderr_writer.flush().await.at_unknown()?;
+            }
+            report.start_time = unix_millis(start_time);
+            report.duration_ms = start.elapsed().as_millis() as u64;
+            report.attempts = attempts;
+            break Ok::<_, Error>(report)
+        }
+    };
+    let progress = async {
+        // the started report is sent while the command is already running so an unreachable server doesn't delay it
+        started.await;
+        let Some(heartbeat) = args.heartbeat else { return future::pending::<()>().await };
+        let mut interval = tokio::time::interval(Duration::from_secs(heartbeat));
+        // the first tick completes immediately
+        interval.tick().await;
+        loop {
+            interval.tick().await;
+            ping(client, config, cronjob, quiet, CronReport::without_status(config, CronState::Running, None, start_time, start)).await;
+        }
+    };
+    // progress reports still being sent when the command finishes are superseded by the final report
+    tokio::select! {
+        report = attempts => Ok(Some(report?)),
+        () = progress => unreachable!("heartbeat loop never exits"),
+    }
+}
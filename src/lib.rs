@@ -2,9 +2,20 @@ use {
     std::{
         cmp::Ordering::*,
         ffi::OsString,
-        io::prelude::*,
+        io::{
+            self,
+            prelude::*,
+        },
+        path::PathBuf,
+        process::{
+            ExitStatus,
+            Output,
+            Stdio,
+        },
+        time::Duration,
     },
     clap as _, // only used in bin target
+    futures::future,
     gethostname::gethostname,
     gix_hash::ObjectId,
     lazy_regex::regex_captures,
@@ -16,48 +27,43 @@ use {
         Deserialize,
         Serialize,
     },
-    tokio::process::Command,
+    tokio::{
+        io::AsyncReadExt as _,
+        process::{
+            Child,
+            Command,
+        },
+    },
     unicode_width::UnicodeWidthStr as _,
     wheel::{
         fs,
         traits::{
-            AsyncCommandOutputExt as _,
             IoResultExt as _,
             IsNetworkError,
         },
     },
 };
 #[cfg(unix)] use {
-    std::{
-        iter,
-        path::{
-            Path,
-            PathBuf,
+    std::path::Path,
+    nix::{
+        sys::signal::{
+            Signal,
+            killpg,
         },
-        process::Stdio,
-        str::FromStr as _,
-    },
-    futures::stream::TryStreamExt as _,
-    lazy_regex::regex_is_match,
-    tokio::io::{
-        AsyncBufReadExt as _,
-        BufReader,
+        unistd::Pid,
     },
-    tokio_stream::wrappers::LinesStream,
-    wheel::fs::File,
 };
 #[cfg(windows)] use {
     directories::ProjectDirs,
     wheel::traits::CommandExt as _,
 };
 #[cfg(feature = "new")] use {
-    itertools::Itertools as _,
-    systemstat::{
-        Platform as _,
-        System,
-    },
+    std::time::Instant,
+    crate::collector::Collector,
 };
 
+#[cfg(feature = "new")] pub mod collector;
+
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
     #[error(transparent)] Wheel(#[from] wheel::Error),
@@ -75,14 +81,26 @@ pub enum ConfigError {
 #[derive(Debug, thiserror::Error)]
 pub enum Error {
     #[error(transparent)] CargoUpdateCheck(#[from] CargoUpdateCheckError),
+    /// An error from a collector defined outside of this crate.
+    #[error(transparent)] Collector(#[from] Box<dyn std::error::Error + Send + Sync>),
     #[error(transparent)] Config(#[from] ConfigError),
     #[error(transparent)] ParseInt(#[from] std::num::ParseIntError),
     #[error(transparent)] Reqwest(#[from] reqwest::Error),
+    #[error(transparent)] Task(#[from] tokio::task::JoinError),
+    #[error(transparent)] Timeout(#[from] TimeoutError),
     #[error(transparent)] TryFromInt(#[from] std::num::TryFromIntError),
     #[error(transparent)] Utf8(#[from] std::string::FromUtf8Error),
     #[error(transparent)] Wheel(#[from] wheel::Error),
+    #[error("no file systems configured")]
+    NoFileSystems,
     #[error("non-UTF-8 string")]
     OsString(OsString),
+    #[cfg(unix)]
+    #[error("failed to parse JSON from journalctl")]
+    JournalJson(String),
+    #[cfg(unix)]
+    #[error("journalVacuum cleanup action needs a size or time limit")]
+    JournalVacuumUnbounded,
     #[cfg(windows)]
     #[error("failed to parse JSON from Scoop status")]
     ScoopJson(String),
@@ -98,34 +116,183 @@ impl IsNetworkError for Error {
     fn is_network_error(&self) -> bool {
         match self {
             Self::CargoUpdateCheck(e) => e.is_network_error(),
+            Self::Collector(_) => false,
             Self::Config(_) => false,
             Self::ParseInt(_) => false,
             Self::Reqwest(e) => e.is_network_error(),
+            Self::Task(_) => false,
+            Self::Timeout(_) => false,
             Self::TryFromInt(_) => false,
             Self::Utf8(_) => false,
             Self::Wheel(e) => e.is_network_error(),
+            Self::NoFileSystems => false,
             Self::OsString(_) => false,
+            #[cfg(unix)] Self::JournalJson(_) => false,
+            #[cfg(unix)] Self::JournalVacuumUnbounded => false,
             #[cfg(windows)] Self::ScoopJson(_) => false,
         }
     }
 }
 
-#[cfg(windows)] fn make_c() -> Vec<String> { vec![format!("C:\\")] }
+fn make_file_systems() -> Vec<String> {
+    #[cfg(unix)] { vec![format!("/")] }
+    #[cfg(windows)] { vec![format!("C:\\")] }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{command} did not finish within {timeout:?}")]
+pub struct TimeoutError {
+    pub command: &'static str,
+    pub timeout: Duration,
+}
+
+/// How long a command which timed out has to exit after SIGTERM before it's killed.
+const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(10);
+
+/// Runs the command and checks its exit status, terminating it if it doesn't finish within the timeout. See [`output_with_timeout`].
+async fn check_with_timeout(name: &'static str, timeout: Duration, command: &mut Command) -> Result<Result<Output, wheel::Error>, TimeoutError> {
+    Ok(output_with_timeout(name, timeout, command).await?.and_then(|output| if output.status.success() {
+        Ok(output)
+    } else {
+        Err(wheel::Error::CommandExit { name: name.into(), output })
+    }))
+}
+
+/// Runs the command and collects its output, terminating it if it doesn't finish within the timeout.
+///
+/// On Unix, the command is spawned as a process group leader and the process group is terminated on timeout.
+/// Unlike the SIGKILL sent by `kill_on_drop`, the SIGTERM sent first is relayed by `sudo`, so commands run via `sudo` don't keep running.
+async fn output_with_timeout(name: &'static str, timeout: Duration, command: &mut Command) -> Result<Result<Output, wheel::Error>, TimeoutError> {
+    #[cfg(unix)] command.process_group(0);
+    // same as `Command::output`
+    command.stdin(Stdio::null());
+    command.stdout(Stdio::piped());
+    command.stderr(Stdio::piped());
+    command.kill_on_drop(true);
+    let mut child = match command.spawn().at_command(name) {
+        Ok(child) => child,
+        Err(e) => return Ok(Err(e)),
+    };
+    let mut stdout_pipe = child.stdout.take().expect("stdout is piped");
+    let mut stderr_pipe = child.stderr.take().expect("stderr is piped");
+    let mut stdout = Vec::default();
+    let mut stderr = Vec::default();
+    let result = tokio::time::timeout(timeout, future::try_join3(
+        child.wait(),
+        stdout_pipe.read_to_end(&mut stdout),
+        stderr_pipe.read_to_end(&mut stderr),
+    )).await;
+    match result {
+        Ok(Ok((status, _, _))) => Ok(Ok(Output { status, stdout, stderr })),
+        Ok(Err(e)) => Ok(Err(e).at_command(name)),
+        Err(_) => {
+            // errors mean the command already exited
+            let _ = terminate(&mut child, TERMINATE_GRACE_PERIOD).await;
+            Err(TimeoutError { command: name, timeout })
+        }
+    }
+}
+
+/// Terminates the child's process group, first with SIGTERM, then with SIGKILL after the grace period. The child must have been spawned as a process group leader.
+///
+/// On Windows, the child is killed immediately.
+pub async fn terminate(child: &mut Child, #[cfg_attr(windows, allow(unused))] grace_period: Duration) -> io::Result<ExitStatus> {
+    #[cfg(unix)] if let Some(pid) = child.id() {
+        let pgid = Pid::from_raw(pid as i32);
+        // errors mean the process group no longer exists
+        let _ = killpg(pgid, Signal::SIGTERM);
+        let result = tokio::time::timeout(grace_period, child.wait()).await;
+        // also kill any remaining processes in the group which could keep output pipes open
+        let _ = killpg(pgid, Signal::SIGKILL);
+        if let Ok(status) = result {
+            return status
+        }
+    }
+    child.kill().await?;
+    child.wait().await
+}
+
+#[cfg(unix)] fn make_oldconffiles() -> Vec<OldConfFiles> { vec![OldConfFiles::User(format!("fenhl")), OldConfFiles::User(format!("pi"))] }
+fn make_server_url() -> String { format!("https://night.fenhl.net") }
 fn make_true() -> bool { true }
 
 #[derive(Deserialize)]
 #[serde(rename_all = "camelCase")]
 pub struct Config {
+    /// How to run `cargo install-update` for the Cargo update checks.
+    #[serde(default)]
+    pub cargo: CargoConfig,
+    /// Cleanup actions to run when disk space is low.
+    #[serde(default)]
+    pub cleanup: CleanupConfig,
+    /// Where to look for the results of cron-apt.
+    #[cfg(unix)]
+    #[serde(default)]
+    pub cron_apt: CronAptConfig,
     pub device_key: String,
-    #[cfg(windows)]
-    #[serde(default = "make_c")]
+    /// Mount points (or volumes on Windows) whose disk space and inodes are reported.
+    #[serde(default = "make_file_systems")]
     pub file_systems: Vec<String>,
     pub hostname: Option<String>,
+    /// Marker files whose existence indicates obsolete configuration files left behind by package upgrades.
+    #[cfg(unix)]
+    #[serde(default = "make_oldconffiles")]
+    pub oldconffiles: Vec<OldConfFiles>,
+    /// Base URL of the Night instance reports are sent to, without a trailing slash.
+    #[serde(default = "make_server_url")]
+    pub server_url: String,
+    /// Whether reports may only be sent via HTTPS. Can be disabled to test against a local server.
+    #[serde(default = "make_true")]
+    pub https_only: bool,
+    /// Whether to assume that the server supports HTTP/2 without negotiating it first.
+    #[serde(default = "make_true")]
+    pub http2_prior_knowledge: bool,
     /// Whether I have root access on this device.
     /// If `true`, night-device-report assumes it is running as `root`.
     /// If `false`, night-device-report skips checks for system updates which should be handled by root.
     #[serde(default = "make_true")]
     pub root: bool,
+    /// Time limits for external commands run by the checks. A command which exceeds its limit is killed and the check reports a timeout error.
+    #[serde(default)]
+    pub timeouts: Timeouts,
+}
+
+#[derive(Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct Timeouts {
+    /// Timeout for each invocation of `cargo install-update`, in seconds.
+    pub cargo_install_update: u64,
+    /// Timeout for each command run by a cleanup action, in seconds.
+    pub cleanup: u64,
+    /// Timeout for `journalctl`, in seconds.
+    #[cfg(unix)]
+    pub journalctl: u64,
+    /// Timeout for `needrestart`, in seconds.
+    #[cfg(unix)]
+    pub needrestart: u64,
+    /// Timeout for `nixos-needsreboot`, in seconds.
+    #[cfg(unix)]
+    pub nixos_needsreboot: u64,
+    /// Timeout for `systemctl`, in seconds.
+    #[cfg(unix)]
+    pub systemctl: u64,
+    /// Timeout for each Scoop command, in seconds.
+    #[cfg(windows)]
+    pub scoop: u64,
+}
+
+impl Default for Timeouts {
+    fn default() -> Self {
+        Self {
+            cargo_install_update: 60 * 60,
+            cleanup: 30 * 60,
+            #[cfg(unix)] journalctl: 60,
+            #[cfg(unix)] needrestart: 5 * 60,
+            #[cfg(unix)] nixos_needsreboot: 5 * 60,
+            #[cfg(unix)] systemctl: 60,
+            #[cfg(windows)] scoop: 30 * 60,
+        }
+    }
 }
 
 impl Config {
@@ -159,6 +326,255 @@ impl Config {
             }
         })
     }
+
+    /// The URL to which device reports are sent.
+    pub fn report_url(&self) -> Result<String, OsString> {
+        Ok(format!("{}/dev/{}/device-report", self.server_url.trim_end_matches('/'), self.hostname()?))
+    }
+
+    /// The URL to which reports for the given cronjob are sent.
+    pub fn cronjob_url(&self, cronjob: &str) -> Result<String, OsString> {
+        Ok(format!("{}/device-report/{}/{cronjob}", self.server_url.trim_end_matches('/'), self.hostname()?))
+    }
+}
+
+#[cfg(unix)]
+#[derive(Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CronAptConfig {
+    /// Which log to read cron-apt's results from.
+    pub log: CronAptLog,
+    /// How far back to look in the systemd journal, in any format accepted by `journalctl --since`.
+    pub since: String,
+}
+
+#[cfg(unix)]
+impl Default for CronAptConfig {
+    fn default() -> Self {
+        Self {
+            log: CronAptLog::default(),
+            since: format!("-2d"),
+        }
+    }
+}
+
+#[cfg(unix)]
+#[derive(Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub enum CronAptLog {
+    /// Use syslog if any of the syslog files exist, otherwise use the systemd journal.
+    #[default]
+    Auto,
+    /// Read `/var/log/syslog` and `/var/log/syslog.1`.
+    Syslog,
+    /// Read messages from the systemd journal using `journalctl`.
+    Journald,
+}
+
+#[cfg(unix)]
+#[derive(Deserialize)]
+#[serde(untagged)]
+pub enum OldConfFiles {
+    /// Checks for an `oldconffiles` file in the home directory of the given user, reported under the username.
+    User(String),
+    /// Checks for the given file, reported under the given name.
+    Path {
+        name: String,
+        path: PathBuf,
+    },
+}
+
+#[cfg(unix)]
+impl OldConfFiles {
+    fn name(&self) -> &str {
+        match self {
+            Self::User(username) => username,
+            Self::Path { name, .. } => name,
+        }
+    }
+
+    fn path(&self) -> PathBuf {
+        match self {
+            Self::User(username) => Path::new("/home").join(username).join("oldconffiles"),
+            Self::Path { path, .. } => path.clone(),
+        }
+    }
+}
+
+/// Configures how Cargo is run. The defaults match the behavior of versions before this section was added,
+/// i.e. `sudo -n -u fenhl /home/fenhl/.cargo/bin/cargo` if `root` is `true` and `/home/fenhl/.cargo/bin/cargo` otherwise.
+/// Devices without a `fenhl` user should set `user` explicitly, or to `null` to run Cargo as the user running night-device-report.
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct CargoConfig {
+    /// The user as which Cargo should be run. Defaults to `fenhl`. If `null`, Cargo is run as the user running night-device-report.
+    #[cfg(unix)]
+    #[serde(default = "make_cargo_user")]
+    pub user: Option<String>,
+    /// Whether to use `sudo` to run Cargo as `user`. Defaults to the value of `root`.
+    #[cfg(unix)]
+    pub sudo: Option<bool>,
+    /// Path to the `cargo` binary.
+    /// Defaults to `bin/cargo` in `cargoHome` if specified, then to `~user/.cargo/bin/cargo` if `user` is specified, then to `cargo` from the `PATH`.
+    pub cargo: Option<PathBuf>,
+    /// Overrides the `CARGO_HOME` environment variable for Cargo.
+    pub cargo_home: Option<PathBuf>,
+}
+
+#[cfg(unix)] fn make_cargo_user() -> Option<String> { Some(format!("fenhl")) }
+
+impl Default for CargoConfig {
+    fn default() -> Self {
+        Self {
+            #[cfg(unix)] user: make_cargo_user(),
+            #[cfg(unix)] sudo: None,
+            cargo: None,
+            cargo_home: None,
+        }
+    }
+}
+
+impl CargoConfig {
+    fn cargo_path(&self) -> PathBuf {
+        if let Some(ref cargo) = self.cargo {
+            return cargo.clone()
+        }
+        if let Some(ref cargo_home) = self.cargo_home {
+            return cargo_home.join("bin").join("cargo")
+        }
+        #[cfg(unix)] if let Some(ref user) = self.user {
+            #[cfg(target_os = "macos")] { return Path::new("/Users").join(user).join(".cargo").join("bin").join("cargo") }
+            #[cfg(not(target_os = "macos"))] { return Path::new("/home").join(user).join(".cargo").join("bin").join("cargo") }
+        }
+        PathBuf::from("cargo")
+    }
+
+    /// Returns a `cargo` command with the configured user and Cargo installation.
+    fn command(&self, #[cfg_attr(windows, allow(unused))] root: bool) -> Command {
+        #[cfg(unix)] let cmd = if self.sudo.unwrap_or(root) {
+            let mut cmd = Command::new("sudo");
+            cmd.arg("-n");
+            if let Some(ref user) = self.user {
+                cmd.arg("-u");
+                cmd.arg(user);
+            }
+            if let Some(ref cargo_home) = self.cargo_home {
+                // sudo resets the environment
+                let mut assignment = OsString::from("CARGO_HOME=");
+                assignment.push(cargo_home);
+                cmd.arg("env");
+                cmd.arg(assignment);
+            }
+            cmd.arg(self.cargo_path());
+            cmd
+        } else {
+            let mut cmd = Command::new(self.cargo_path());
+            if let Some(ref cargo_home) = self.cargo_home {
+                cmd.env("CARGO_HOME", cargo_home);
+            }
+            cmd
+        };
+        #[cfg(windows)] let cmd = {
+            let mut cmd = Command::new(self.cargo_path());
+            if let Some(ref cargo_home) = self.cargo_home {
+                cmd.env("CARGO_HOME", cargo_home);
+            }
+            cmd.release_create_no_window();
+            cmd
+        };
+        cmd
+    }
+
+    /// Returns a `cargo install-update` command with the configured user and Cargo installation.
+    fn install_update_command(&self, root: bool) -> Command {
+        let mut cmd = self.command(root);
+        cmd.arg("install-update");
+        cmd
+    }
+}
+
+#[derive(Default, Deserialize)]
+#[serde(default, rename_all = "camelCase")]
+pub struct CleanupConfig {
+    /// Run the cleanup actions if any configured file system has less than this fraction of its disk space free.
+    pub min_free_ratio: Option<f64>,
+    /// Run the cleanup actions if any configured file system has less than this many bytes free.
+    pub min_free_bytes: Option<u64>,
+    /// The cleanup actions to run, in order.
+    pub actions: Vec<CleanupAction>,
+}
+
+impl CleanupConfig {
+    #[cfg(feature = "new")]
+    fn is_low(&self, fs: &FileSystemUsage) -> bool {
+        self.min_free_ratio.is_some_and(|min_free_ratio| fs.free_ratio() < min_free_ratio)
+        || self.min_free_bytes.is_some_and(|min_free_bytes| fs.diskspace_free < min_free_bytes)
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase", rename_all_fields = "camelCase")]
+pub enum CleanupAction {
+    /// Runs `cargo sweep -ir` on each of the given directories, using the configured Cargo installation.
+    CargoSweep {
+        paths: Vec<PathBuf>,
+    },
+    /// Runs `apt-get clean`.
+    #[cfg(unix)]
+    AptClean,
+    /// Runs `nix-collect-garbage --delete-older-than`.
+    #[cfg(unix)]
+    NixCollectGarbage {
+        /// A period in the format accepted by `nix-collect-garbage`, e.g. `30d`.
+        delete_older_than: String,
+    },
+    /// Runs `journalctl --vacuum-size` and/or `--vacuum-time`. At least one of `size` and `time` must be specified.
+    #[cfg(unix)]
+    JournalVacuum {
+        size: Option<String>,
+        time: Option<String>,
+    },
+}
+
+impl CleanupAction {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::CargoSweep { .. } => "cargo sweep",
+            #[cfg(unix)] Self::AptClean => "apt-get clean",
+            #[cfg(unix)] Self::NixCollectGarbage { .. } => "nix-collect-garbage",
+            #[cfg(unix)] Self::JournalVacuum { .. } => "journalctl --vacuum",
+        }
+    }
+}
+
+/// Returns the directory where reports which could not be sent due to network errors are kept until the next run, creating it if necessary.
+pub async fn spool_dir() -> Result<PathBuf, ConfigError> {
+    #[cfg(unix)] {
+        Ok(xdg::BaseDirectories::with_prefix("fenhl/night").create_state_directory("spool").at_unknown()?)
+    }
+    #[cfg(windows)] {
+        let path = ProjectDirs::from("net", "Fenhl", "Night").ok_or(ConfigError::ProjectDirs)?.data_local_dir().join("spool");
+        fs::create_dir_all(&path).await?;
+        Ok(path)
+    }
+}
+
+/// Returns the directory where lock files for cronjobs are kept, creating it if necessary.
+pub async fn lock_dir() -> Result<PathBuf, ConfigError> {
+    #[cfg(unix)] {
+        let base_dirs = xdg::BaseDirectories::with_prefix("fenhl/night");
+        Ok(if base_dirs.has_runtime_directory() {
+            base_dirs.create_runtime_directory("cronjob-locks")
+        } else {
+            // e.g. system cronjobs, which don't run in a login session
+            base_dirs.create_state_directory("cronjob-locks")
+        }.at_unknown()?)
+    }
+    #[cfg(windows)] {
+        let path = ProjectDirs::from("net", "Fenhl", "Night").ok_or(ConfigError::ProjectDirs)?.data_local_dir().join("cronjob-locks");
+        fs::create_dir_all(&path).await?;
+        Ok(path)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -169,17 +585,137 @@ pub struct ReportData {
     pub cargo_update_check_error_display: Option<String>,
     pub cargo_updates: Option<HashMap<String, [Version; 2]>>,
     pub cargo_updates_git: Option<HashMap<String, [ObjectId; 2]>>,
+    /// Cleanup actions which were run because disk space was low.
+    #[serde(default)]
+    pub cleanup: Vec<CleanupResult>,
     pub cron_apt: bool,
     pub diskspace_total: u64,
     pub diskspace_free: u64,
+    /// Usage of each configured file system, keyed by mount point. The `diskspace_*` and `inodes_*` fields summarize the one with the lowest ratio of free disk space.
+    #[serde(default)]
+    pub filesystems: HashMap<String, FileSystemUsage>,
     pub inodes_total: u64,
     pub inodes_free: u64,
     pub needrestart: Option<u8>,
+    /// The full output of `needrestart`. `None` on systems where needrestart isn't used.
+    #[serde(default)]
+    pub needrestart_details: Option<NeedRestartDetails>,
+    /// The last run of the automatic upgrade service on NixOS. `None` on other systems or if automatic upgrades are not configured.
+    #[serde(default)]
+    pub nixos_upgrade: Option<NixosUpgrade>,
     pub oldconffiles: HashMap<String, bool>,
     pub os_version: os_info::Version,
+    /// Why a reboot is needed, as reported by `nixos-needsreboot`. Empty on systems other than NixOS.
+    #[serde(default)]
+    pub reboot_reasons: Vec<RebootReason>,
     pub running_os: os_info::Type,
     #[serde(default)]
     pub scoop_updates: Vec<ScoopUpdate>,
+    /// Errors from collectors which failed, keyed by collector name. The sections these collectors would have filled in are left at their defaults.
+    #[serde(default)]
+    pub section_errors: HashMap<String, SectionError>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "async-proto", derive(async_proto::Protocol))]
+pub struct SectionError {
+    pub debug: String,
+    pub display: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "async-proto", derive(async_proto::Protocol))]
+#[serde(rename_all = "camelCase")]
+pub struct CleanupResult {
+    pub action: String,
+    /// Disk space freed by this action, in bytes, summed over the configured file systems.
+    pub reclaimed: u64,
+    /// The error message if the action failed.
+    pub error: Option<String>,
+    /// The action was not run because this report was generated with `--dry-run`.
+    #[serde(default)]
+    pub planned: bool,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "async-proto", derive(async_proto::Protocol))]
+#[serde(rename_all = "camelCase")]
+pub struct FileSystemUsage {
+    pub diskspace_total: u64,
+    pub diskspace_free: u64,
+    pub inodes_total: u64,
+    pub inodes_free: u64,
+}
+
+impl FileSystemUsage {
+    #[cfg(feature = "new")]
+    fn new(fs: &systemstat::Filesystem) -> Result<Self, std::num::TryFromIntError> {
+        Ok(Self {
+            diskspace_total: fs.total.as_u64(),
+            diskspace_free: fs.avail.as_u64(),
+            inodes_total: fs.files_total.try_into()?,
+            inodes_free: fs.files_avail.try_into()?,
+        })
+    }
+
+    #[cfg(feature = "new")]
+    fn free_ratio(&self) -> f64 {
+        self.diskspace_free as f64 / self.diskspace_total as f64
+    }
+}
+
+#[derive(Debug, Default, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "async-proto", derive(async_proto::Protocol))]
+#[serde(rename_all = "camelCase")]
+pub struct NeedRestartDetails {
+    /// The running kernel version (`NEEDRESTART-KCUR`).
+    pub kernel_current: Option<String>,
+    /// The kernel version which would be running after a reboot (`NEEDRESTART-KEXP`).
+    pub kernel_expected: Option<String>,
+    /// `NEEDRESTART-KSTA`: 0 = unknown, 1 = current, 2 = ABI compatible upgrade pending, 3 = version upgrade pending.
+    pub kernel_status: Option<u8>,
+    /// `NEEDRESTART-UCSTA`: 0 = unknown, 1 = current, 2 = outdated.
+    pub microcode_status: Option<u8>,
+    /// The running microcode revision (`NEEDRESTART-UCCUR`).
+    pub microcode_current: Option<String>,
+    /// The available microcode revision (`NEEDRESTART-UCEXP`).
+    pub microcode_expected: Option<String>,
+    /// Services which need to be restarted (`NEEDRESTART-SVC`).
+    pub services: Vec<String>,
+    /// Containers which need to be restarted (`NEEDRESTART-CONT`).
+    pub containers: Vec<String>,
+    /// User sessions running outdated binaries (`NEEDRESTART-SESS`).
+    pub sessions: Vec<String>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "async-proto", derive(async_proto::Protocol))]
+#[serde(rename_all = "camelCase")]
+pub struct NixosUpgrade {
+    /// When `nixos-upgrade.service` was last started, in seconds since the Unix epoch. `None` if it hasn't run since boot.
+    pub last_run: Option<i64>,
+    /// systemd's result for the last run, e.g. `success` or `exit-code`.
+    pub result: String,
+    /// The exit status of the last run.
+    pub exit_status: Option<i32>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "async-proto", derive(async_proto::Protocol))]
+pub struct RebootReason {
+    pub component: RebootComponent,
+    /// The line of `nixos-needsreboot` output this reason was parsed from.
+    pub raw: String,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[cfg_attr(feature = "async-proto", derive(async_proto::Protocol))]
+#[serde(rename_all = "camelCase")]
+pub enum RebootComponent {
+    Kernel,
+    Initrd,
+    Systemd,
+    Other,
 }
 
 #[derive(Debug, Clone, Deserialize, Serialize)]
@@ -199,168 +735,76 @@ pub struct ScoopUpdate {
 
 impl ReportData {
     #[cfg(feature = "new")]
-    pub async fn new(config: &Config, verbose: bool) -> Result<Self, Error> {
-        if verbose { println!("checking Cargo updates") }
-        let (cargo_updates, cargo_updates_git, cargo_update_check_error_debug, cargo_update_check_error_display) = match check_cargo_updates(config.root, true).await {
-            Ok((cargo_updates, cargo_updates_git)) => if !cargo_updates.is_empty() || !cargo_updates_git.is_empty() {
-                let command = {
-                    #[cfg(unix)] {
-                        let mut cmd;
-                        if config.root {
-                            cmd = Command::new("sudo");
-                            cmd.arg("-n");
-                            cmd.arg("-u");
-                            cmd.arg("fenhl");
-                            #[cfg(target_os = "macos")] { cmd.arg("/Users/fenhl/.cargo/bin/cargo"); }
-                            #[cfg(not(target_os = "macos"))] { cmd.arg("/home/fenhl/.cargo/bin/cargo"); }
-                        } else {
-                            #[cfg(target_os = "macos")] { cmd = Command::new("/Users/fenhl/.cargo/bin/cargo"); }
-                            #[cfg(not(target_os = "macos"))] { cmd = Command::new("/home/fenhl/.cargo/bin/cargo"); }
-                        }
-                        cmd.arg("install-update");
-                        cmd.arg("--all");
-                        cmd.arg("--git");
-                        cmd
-                    }
-                    #[cfg(windows)] {
-                        let mut cmd = Command::new("cargo");
-                        cmd.arg("install-update");
-                        cmd.arg("--all");
-                        cmd.arg("--git");
-                        cmd.release_create_no_window();
-                        cmd
-                    }
-                };
-                if verbose { println!("attempting to install Cargo updates") }
-                if let Err(e) = command.check("cargo install-update").await {
-                    if verbose { println!("installing Cargo updates failed, reporting") }
-                    (Some(cargo_updates), Some(cargo_updates_git), Some(format!("{e:?}")), Some(e.to_string()))
-                } else {
-                    if verbose { println!("Cargo updates successfully installed") }
-                    (Some(HashMap::default()), Some(HashMap::default()), None, None)
-                }
-            } else {
-                (Some(cargo_updates), Some(cargo_updates_git), None, None)
-            },
-            Err(e) => (None, None, Some(format!("{e:?}")), Some(e.to_string())),
-        };
+    pub async fn new(config: &Config, verbose: bool, dry_run: bool) -> Self {
+        Self::collect(config, &collector::default_collectors(), verbose, dry_run).await
+    }
+
+    /// Builds a report by running the given collectors concurrently. Collectors which don't apply to the running operating system are skipped.
+    /// Collectors for which [`Collector::run_last`] is `true` are started once all others have finished.
+    /// Sections are written into the report in the order of the collectors.
+    ///
+    /// A failing collector does not abort the report, its error is recorded in `section_errors` instead.
+    #[cfg(feature = "new")]
+    pub async fn collect(config: &Config, collectors: &[Box<dyn Collector>], verbose: bool, dry_run: bool) -> Self {
         if verbose { println!("checking OS info") }
         let os_info = os_info::get();
-        #[cfg(unix)] {
-            if verbose { println!("checking file system") }
-            let fs = System::new().mount_at("/").at("/")?;
-            //TODO if low on disk space, run cargo sweep (`cargo sweep -ir` on non-NixOS, need to determine toolchains to keep on NixOS)
-            Ok(Self {
-                cron_apt: config.root && if let os_info::Type::NixOS = os_info.os_type() {
-                    false // updates are configured to be installed automatically, TODO verify nixos-upgrade.service exited successfully
-                } else {
-                    // not NixOS, assume Debian
-                    if verbose { println!("checking cron-apt logs") }
-                    let mut cron_apt = true;
-                    let syslogs = vec![Path::new("/var/log/syslog"), Path::new("/var/log/syslog.1")];
-                    'cron_apt: for log_path in syslogs {
-                        if log_path.exists() {
-                            let log_f = BufReader::new(File::open(log_path).await?);
-                            for line in LinesStream::new(log_f.lines()).try_collect::<Vec<_>>().await.at(log_path)?.into_iter().rev() {
-                                if line.contains("cron-apt: Download complete and in download only mode") {
-                                    break 'cron_apt
-                                } else if line.contains("cron-apt: 0 upgraded, 0 newly installed, 0 to remove and 0 not upgraded.") {
-                                    cron_apt = false;
-                                    break 'cron_apt
-                                }
-                            }
-                        }
-                    }
-                    cron_apt
-                },
-                diskspace_total: fs.total.as_u64(),
-                diskspace_free: fs.avail.as_u64(),
-                inodes_total: fs.files_total.try_into()?,
-                inodes_free: fs.files_avail.try_into()?,
-                needrestart: match os_info.os_type() { // emulate NEEDRESTART-KSTA codes
-                    os_info::Type::Macos => Some(1), // update workflow includes reboot
-                    os_info::Type::NixOS => if config.root {
-                        if verbose { println!("checking nixos-needsreboot") }
-                        let output = Command::new("nixos-needsreboot").output().await.at_command("nixos-needsreboot")?;
-                        match output.status.code() {
-                            Some(0) => Some(1), // no reboot needed
-                            Some(2) => Some(2), // reboot needed //TODO use 3 if it's specifically for a new kernel version (check stderr)
-                            Some(1) if regex_is_match!("nixos-needsreboot: I/O error at /nix/store/.+/lib/modules: No such file or directory \\(os error 2\\)", &String::from_utf8_lossy(&output.stderr)) => Some(3), // NixOS seems to delete old kernel modules after upgrade
-                            code => {
-                                if let Some(code) = code {
-                                    eprintln!("nixos-needsreboot exited with status code {code}");
-                                } else {
-                                    eprintln!("nixos-needsreboot exited with no status code");
-                                }
-                                Some(0) // unknown status
-                            }
-                        }
-                    } else {
-                        None
-                    },
-                    _ => if config.root {
-                        if verbose { println!("checking needrestart") }
-                        String::from_utf8(Command::new("/usr/sbin/needrestart").arg("-b").stderr(Stdio::null()).output().await.at_command("needrestart")?.stdout)?.lines()
-                            .find_map(|line| line.strip_prefix("NEEDRESTART-KSTA: "))
-                            .map(|line| line.parse())
-                            .transpose()?
-                    } else {
-                        None
-                    },
-                },
-                oldconffiles: {
-                    if verbose { println!("checking oldconffiles") }
-                    ["fenhl", "pi"].into_iter()
-                        .map(|username| (username.into(), Path::new("/home").join(username).join("oldconffiles").exists()))
-                        .collect()
-                },
-                os_version: if let os_info::Type::Debian = os_info.os_type() {
-                    // os_info only reports major version, get more accurate version info from file
-                    if verbose { println!("checking Debian version") }
-                    let [major, minor, patch] = fs::read_to_string("/etc/debian_version").await?.trim_end().split('.').map(u64::from_str).chain(iter::repeat(Ok(0))).next_array().expect("iter::repeat produces an infinite iterator");
-                    os_info::Version::Semantic(major?, minor?, patch?)
-                } else {
-                    os_info.version().clone()
-                },
-                running_os: os_info.os_type(),
-                scoop_updates: Vec::default(),
-                cargo_updates, cargo_updates_git, cargo_update_check_error_debug, cargo_update_check_error_display,
+        let mut report = Self {
+            cargo_update_check_error_debug: None,
+            cargo_update_check_error_display: None,
+            cargo_updates: None,
+            cargo_updates_git: None,
+            cleanup: Vec::default(),
+            cron_apt: false,
+            diskspace_total: 0,
+            diskspace_free: 0,
+            filesystems: HashMap::default(),
+            inodes_total: 0,
+            inodes_free: 0,
+            needrestart: None,
+            needrestart_details: None,
+            nixos_upgrade: None,
+            oldconffiles: HashMap::default(),
+            os_version: os_info.version().clone(),
+            reboot_reasons: Vec::default(),
+            running_os: os_info.os_type(),
+            scoop_updates: Vec::default(),
+            section_errors: HashMap::default(),
+        };
+        let ctx = collector::Context { config, os_info: &os_info, verbose, dry_run };
+        let run = |run_last| future::join_all(collectors.iter()
+            .enumerate()
+            .filter(|(_, collector)| collector.applies_to(os_info.os_type()) && collector.run_last() == run_last)
+            .map(|(idx, collector)| {
+                let ctx = &ctx;
+                async move {
+                    if verbose { println!("checking {}", collector.name()) }
+                    let start = Instant::now();
+                    let result = collector.collect(ctx).await;
+                    if verbose { println!("{} check finished after {:.2?}", collector.name(), start.elapsed()) }
+                    (idx, collector.name(), result)
+                }
             })
+        );
+        let mut results = run(false).await;
+        results.extend(run(true).await);
+        results.sort_by_key(|&(idx, _, _)| idx);
+        let mut sections = Vec::with_capacity(results.len());
+        for (_, name, result) in results {
+            match result {
+                Ok(section) => sections.push(section),
+                Err(e) => {
+                    if verbose { println!("{name} check failed, reporting") }
+                    report.section_errors.insert(name.to_owned(), SectionError {
+                        debug: format!("{e:?}"),
+                        display: e.to_string(),
+                    });
+                }
+            }
         }
-        #[cfg(windows)] {
-            if verbose { println!("checking file system") }
-            let sys = System::new();
-            let fs = config.file_systems.iter()
-                .map(|vol| sys.mount_at(vol).at(vol))
-                .process_results(|vols| vols.min_by(|fs1, fs2| (fs1.avail.as_u64() as f64 / fs1.total.as_u64() as f64).total_cmp(&(fs2.avail.as_u64() as f64 / fs2.total.as_u64() as f64))))?
-                .expect("should be nonempty if there was no error");
-            Ok(Self {
-                cron_apt: true, // see night-windows-service crate in private night repo for a way to actually check for updates
-                diskspace_total: fs.total.as_u64(),
-                diskspace_free: fs.avail.as_u64(),
-                inodes_total: fs.files_total.try_into()?,
-                inodes_free: fs.files_avail.try_into()?,
-                needrestart: Some(2), // see night-windows-service crate in private night repo for a way to actually check for updates
-                oldconffiles: HashMap::default(),
-                os_version: os_info.version().clone(),
-                running_os: os_info.os_type(),
-                scoop_updates: {
-                    if verbose { println!("checking Scoop updates") }
-                    Command::new("powershell").arg("-Command").arg("scoop update").release_create_no_window().check("scoop update").await?;
-                    Command::new("powershell").arg("-Command").arg("scoop update --all").release_create_no_window().check("scoop update --all").await?;
-                    let stdout = Command::new("powershell").arg("-Command").arg("scoop status | ConvertTo-Json").release_create_no_window().check("scoop status | ConvertTo-Json").await?.stdout;
-                    if stdout == b"Scoop is up to date.\nEverything is ok!\n" {
-                        Vec::default()
-                    } else {
-                        (0..stdout.len())
-                            .find_map(|idx| serde_json::from_slice::<serde_with::de::DeserializeAsWrap<Vec<ScoopUpdate>, serde_with::OneOrMany<serde_with::Same>>>(&stdout[idx..]).ok())
-                            .ok_or_else(|| Error::ScoopJson(String::from_utf8_lossy(&stdout).into_owned()))?
-                            .into_inner()
-                    }
-                },
-                cargo_updates, cargo_updates_git, cargo_update_check_error_debug, cargo_update_check_error_display,
-            })
+        for section in sections {
+            section(&mut report);
         }
+        report
     }
 }
 
@@ -368,6 +812,7 @@ impl ReportData {
 pub enum CargoUpdateCheckError {
     #[error(transparent)] GitHash(#[from] gix_hash::decode::Error),
     #[error(transparent)] SemVer(#[from] semver::Error),
+    #[error(transparent)] Timeout(#[from] TimeoutError),
     #[error(transparent)] Wheel(#[from] wheel::Error),
     #[error("`cargo install-update` listed multiple packages with the same name")]
     DuplicatePackage,
@@ -386,6 +831,7 @@ impl IsNetworkError for CargoUpdateCheckError {
         match self {
             Self::GitHash(_) => false,
             Self::SemVer(_) => false,
+            Self::Timeout(_) => false,
             Self::Wheel(e) => e.is_network_error(),
             Self::DuplicatePackage => false,
             Self::MissingTableHeader => false,
@@ -396,7 +842,7 @@ impl IsNetworkError for CargoUpdateCheckError {
     }
 }
 
-pub async fn check_cargo_updates(#[cfg_attr(windows, allow(unused))] root: bool, git: bool) -> Result<(HashMap<String, [Version; 2]>, HashMap<String, [ObjectId; 2]>), CargoUpdateCheckError> {
+pub async fn check_cargo_updates(config: &CargoConfig, root: bool, git: bool, timeout: Duration) -> Result<(HashMap<String, [Version; 2]>, HashMap<String, [ObjectId; 2]>), CargoUpdateCheckError> {
     fn split_at_width(s: &str, width: usize) -> Result<[&str; 2], CargoUpdateCheckError> {
         let mut idx = s.ceil_char_boundary(width);
         Ok(loop {
@@ -414,39 +860,12 @@ pub async fn check_cargo_updates(#[cfg_attr(windows, allow(unused))] root: bool,
         })
     }
 
-    let command = {
-        #[cfg(unix)] {
-            let mut cmd;
-            if root {
-                cmd = Command::new("sudo");
-                cmd.arg("-n");
-                cmd.arg("-u");
-                cmd.arg("fenhl");
-                #[cfg(target_os = "macos")] { cmd.arg("/Users/fenhl/.cargo/bin/cargo"); }
-                #[cfg(not(target_os = "macos"))] { cmd.arg("/home/fenhl/.cargo/bin/cargo"); }
-            } else {
-                #[cfg(target_os = "macos")] { cmd = Command::new("/Users/fenhl/.cargo/bin/cargo"); }
-                #[cfg(not(target_os = "macos"))] { cmd = Command::new("/home/fenhl/.cargo/bin/cargo"); }
-            }
-            cmd.arg("install-update");
-            cmd.arg("--list");
-            if git {
-                cmd.arg("--git");
-            }
-            cmd
-        }
-        #[cfg(windows)] {
-            let mut cmd = Command::new("cargo");
-            cmd.arg("install-update");
-            cmd.arg("--list");
-            if git {
-                cmd.arg("--git");
-            }
-            cmd.release_create_no_window();
-            cmd
-        }
-    };
-    let output = command.check("cargo install-update").await?;
+    let mut command = config.install_update_command(root);
+    command.arg("--list");
+    if git {
+        command.arg("--git");
+    }
+    let output = check_with_timeout("cargo install-update", timeout, &mut command).await??;
     let mut lines = BufRead::lines(&*output.stdout);
     let mut cargo_updates = HashMap::default();
     let (package_width, installed_width, latest_width) = loop {
@@ -5,67 +5,251 @@ static GLOBAL: mimalloc::MiMalloc = mimalloc::MiMalloc;
 
 use {
     std::{
+        collections::HashSet,
         env,
         ffi::OsString,
-        time::Duration,
+        fs::{
+            File,
+            OpenOptions,
+        },
+        io::prelude::*,
+        path::{
+            Path,
+            PathBuf,
+        },
+        time::{
+            Duration,
+            SystemTime,
+        },
+    },
+    serde::{
+        Deserialize,
+        Serialize,
+    },
+    wheel::{
+        fs,
+        traits::{
+            IoResultExt as _,
+            IsNetworkError as _,
+        },
     },
-    serde::Serialize,
-    tokio::process::Command,
-    wheel::traits::IoResultExt as _,
     night_device_report::{
         Config,
         Error,
         ReportData,
+        spool_dir,
     },
+    crate::cron::CronReport,
 };
 
-#[derive(Serialize)]
-#[serde(rename_all = "camelCase")]
-struct CronReport {
-    key: String,
-    status: Option<i32>,
+mod cron;
+
+/// A report along with the endpoint it should be sent to. Reports which fail to send due to a network error are spooled to disk in this form.
+#[derive(Deserialize, Serialize)]
+#[serde(tag = "kind", rename_all = "camelCase")]
+enum Submission {
+    Device {
+        data: ReportData,
+    },
+    Cronjob {
+        cronjob: String,
+        data: CronReport,
+    },
+}
+
+impl Submission {
+    fn kind(&self) -> &'static str {
+        match self {
+            Self::Device { .. } => "device",
+            Self::Cronjob { .. } => "cronjob",
+        }
+    }
+
+    async fn send(&self, client: &reqwest::Client, config: &Config) -> Result<(), Error> {
+        match self {
+            Self::Device { data } => client.post(&config.report_url()?)
+                .bearer_auth(&config.device_key)
+                .json(data)
+                .send().await?
+                .error_for_status()?,
+            Self::Cronjob { cronjob, data } => client.post(&config.cronjob_url(cronjob)?)
+                .json(data)
+                .send().await?
+                .error_for_status()?,
+        };
+        Ok(())
+    }
+
+    async fn spool(&self, spool_dir: &Path) -> Result<(), Error> {
+        if let Self::Device { .. } = self {
+            // only the most recent device report is relevant, so older ones are superseded
+            for path in spooled_paths(spool_dir)? {
+                if path.file_name().is_some_and(|name| name.to_string_lossy().ends_with("-device.json")) {
+                    fs::remove_file(path).await?;
+                }
+            }
+        }
+        let timestamp = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH).expect("system clock is before 1970").as_nanos();
+        fs::write(spool_dir.join(format!("{timestamp:020}-{}.json", self.kind())), serde_json::to_vec(self).expect("reports are always serializable")).await?;
+        Ok(())
+    }
+
+    /// Sends this report after any previously spooled ones, spooling it if the server can't be reached.
+    async fn submit(self, client: &reqwest::Client, config: &Config, quiet: bool) -> Result<(), Error> {
+        let spool_dir = spool_dir().await?;
+        let flushed = {
+            let _lock = lock_spool(&spool_dir).await?;
+            flush_spool(client, config, &spool_dir).await
+        };
+        let result = match flushed {
+            Ok(()) => self.send(client, config).await,
+            Err(e) if e.is_network_error() => Err(e),
+            Err(e) => {
+                eprintln!("failed to send spooled reports: {e}");
+                self.send(client, config).await
+            }
+        };
+        match result {
+            Ok(()) => Ok(()),
+            Err(e) if e.is_network_error() => {
+                let _lock = lock_spool(&spool_dir).await?;
+                self.spool(&spool_dir).await?;
+                if !quiet { eprintln!("failed to send report, will retry on next run: {e}") }
+                Ok(())
+            }
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Waits until no other process is using the spool directory, e.g. to avoid sending the same spooled reports from several cronjobs which started at the same time. The lock is released when the returned file is closed.
+async fn lock_spool(spool_dir: &Path) -> Result<File, Error> {
+    // not a JSON file, so it's not mistaken for a spooled report
+    let path = spool_dir.join("lock");
+    let file = OpenOptions::new().create(true).write(true).truncate(false).open(&path).at(&path)?;
+    Ok(tokio::task::spawn_blocking(move || file.lock().map(|()| file)).await?.at(path)?)
+}
+
+/// Lists the spooled reports, oldest first.
+fn spooled_paths(spool_dir: &Path) -> Result<Vec<PathBuf>, Error> {
+    let mut paths = Vec::default();
+    for entry in std::fs::read_dir(spool_dir).at(spool_dir)? {
+        let path = entry.at(spool_dir)?.path();
+        if path.extension().is_some_and(|ext| ext == "json") {
+            paths.push(path);
+        }
+    }
+    // file names start with a zero-padded timestamp
+    paths.sort();
+    Ok(paths)
+}
+
+/// Sends previously spooled reports, oldest first. Stops at the first network error.
+async fn flush_spool(client: &reqwest::Client, config: &Config, spool_dir: &Path) -> Result<(), Error> {
+    let mut sent = HashSet::new();
+    for path in spooled_paths(spool_dir)? {
+        let json = fs::read_to_string(&path).await?;
+        if !sent.contains(&json) {
+            match serde_json::from_str::<Submission>(&json) {
+                Ok(submission) => match submission.send(client, config).await {
+                    Ok(()) => {}
+                    Err(e) if e.is_network_error() => return Err(e),
+                    Err(e) => eprintln!("discarding spooled report {} rejected by the server: {e}", path.display()),
+                },
+                Err(e) => eprintln!("discarding unreadable spooled report {}: {e}", path.display()),
+            }
+            sent.insert(json);
+        }
+        fs::remove_file(path).await?;
+    }
+    Ok(())
+}
+
+fn build_client(config: &Config) -> Result<reqwest::Client, Error> {
+    let mut client = reqwest::Client::builder()
+        .user_agent(concat!("night-device-report/", env!("CARGO_PKG_VERSION")))
+        .timeout(Duration::from_secs(600))
+        // limits how long concurrent runs wait for each other to flush the spool if the server is unreachable
+        .connect_timeout(Duration::from_secs(30))
+        .use_rustls_tls()
+        .https_only(config.https_only);
+    if config.http2_prior_knowledge {
+        client = client.http2_prior_knowledge();
+    }
+    Ok(client.build()?)
+}
+
+/// Runs and reports a cronjob, returning the exit status for the wrapper.
+async fn run_cronjob(cronjob: String, cmd: OsString, cmd_args: Vec<OsString>, args: &cron::CronArgs, quiet: bool) -> Result<i32, Error> {
+    let config = Config::load().await?;
+    let client = build_client(&config)?;
+    Ok(if let Some(data) = cron::run(&client, &config, quiet, &cronjob, cmd, cmd_args, args).await? {
+        let exit_code = data.exit_code();
+        Submission::Cronjob { cronjob, data }.submit(&client, &config, quiet).await?;
+        exit_code
+    } else {
+        0
+    })
 }
 
 #[derive(clap::Parser)]
-#[clap(version)]
+#[clap(version, after_help = "\
+In cronjob mode, exits with the command's exit status, or 128 plus the signal number if it was killed by a signal. Other exit statuses in cronjob mode:
+  124  the command exceeded --timeout
+  125  the cronjob could not be run or reported
+  126  the command could not be started
+  127  the command was not found")]
 struct Args {
     #[clap(short, long)]
     quiet: bool,
+    /// Print the device report as JSON instead of sending it to the server.
+    #[clap(long, visible_alias = "print", conflicts_with = "cronjob")]
+    dry_run: bool,
+    /// Pretty-print the JSON output of --dry-run.
+    #[clap(long, requires = "dry_run")]
+    pretty: bool,
+    /// Write the JSON output of --dry-run to this file instead of stdout.
+    #[clap(short, long, requires = "dry_run")]
+    output: Option<PathBuf>,
     #[clap(requires = "cmd")]
     cronjob: Option<String>,
     cmd: Option<OsString>,
     args: Vec<OsString>,
+    #[clap(flatten)]
+    cron: cron::CronArgs,
 }
 
 #[wheel::main]
 async fn main(args: Args) -> Result<(), Error> {
     let _ = rustls::crypto::ring::default_provider().install_default();
-    let config = Config::load().await?;
-    let client = reqwest::Client::builder()
-        .user_agent(concat!("night-device-report/", env!("CARGO_PKG_VERSION")))
-        .timeout(Duration::from_secs(600))
-        .http2_prior_knowledge()
-        .use_rustls_tls()
-        .https_only(true)
-        .build()?;
     if let (Some(cronjob), Some(cmd)) = (args.cronjob, args.cmd) {
-        let status = Command::new(&cmd).args(args.args).status().await.at_command(cmd.to_string_lossy().into_owned())?;
-        let data = CronReport {
-            key: config.device_key.clone(),
-            status: status.code(),
-        };
-        client.post(&format!("https://night.fenhl.net/device-report/{}/{}", config.hostname()?, cronjob))
-            .json(&data)
-            .send().await?
-            .error_for_status()?;
+        let exit_code = run_cronjob(cronjob, cmd, args.args, &args.cron, args.quiet).await.unwrap_or_else(|e| {
+            eprintln!("error in cronjob wrapper: {e}");
+            cron::EXIT_WRAPPER_FAILED
+        });
+        std::process::exit(exit_code)
+    }
+    let config = Config::load().await?;
+    if args.dry_run {
+        // progress messages would be mixed into the JSON if it goes to stdout
+        let verbose = !args.quiet && args.output.is_some();
+        let data = ReportData::new(&config, verbose, true).await;
+        let mut json = if args.pretty {
+            serde_json::to_vec_pretty(&data)
+        } else {
+            serde_json::to_vec(&data)
+        }.expect("report data is always serializable");
+        json.push(b'\n');
+        if let Some(output) = args.output {
+            fs::write(output, json).await?;
+        } else {
+            std::io::stdout().write_all(&json).at_unknown()?;
+        }
     } else {
-        let data = ReportData::new(&config, !args.quiet).await?;
+        let client = build_client(&config)?;
+        let data = ReportData::new(&config, !args.quiet, false).await;
         if !args.quiet { println!("sending report") }
-        client.post(&format!("https://night.fenhl.net/dev/{}/device-report", config.hostname()?))
-            .bearer_auth(&config.device_key)
-            .json(&data)
-            .send().await?
-            .error_for_status()?;
+        Submission::Device { data }.submit(&client, &config, args.quiet).await?;
         if !args.quiet { println!("done") }
     }
     Ok(())